@@ -5,16 +5,39 @@ use csv_async::{AsyncDeserializer, AsyncReaderBuilder, AsyncSerializer, Trim};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::env;
+use thiserror::Error;
 use tokio::fs::File;
 use tokio::io;
+use tokio::sync::mpsc;
 use tokio_stream::StreamExt;
 
-#[derive(Debug)]
+/// Reasons a transaction can be refused instead of silently applied.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+enum LedgerError {
+    #[error("account does not have enough available funds for this withdrawal")]
+    NotEnoughFunds,
+    #[error("transaction {1} is unknown for client {0}")]
+    UnknownTx(u16, u32),
+    #[error("transaction {0} is already disputed")]
+    AlreadyDisputed(u32),
+    #[error("transaction {0} is not under dispute")]
+    NotDisputed(u32),
+    #[error("account is frozen")]
+    FrozenAccount,
+    #[error("transaction amount is missing or invalid for this transaction type")]
+    MissingAmount,
+    #[error("transaction {0} has already been used")]
+    DuplicateTx(u32),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Account {
     available: Decimal,
     held: Decimal,
-    transactions: BTreeMap<u32, RecordedTransaction>,
+    // keyed by transaction id; the u64 is the arrival-order stamp assigned when the entry was recorded
+    transactions: BTreeMap<u32, (u64, RecordedTransaction)>,
     locked: bool,
 }
 
@@ -40,106 +63,131 @@ impl Account {
         }
     }
 
-    fn deposit(&mut self, (transaction_id, amount): (u32, Decimal)) {
+    fn deposit(
+        &mut self,
+        (transaction_id, amount): (u32, Decimal),
+        stamp: u64,
+    ) -> Result<(), LedgerError> {
         // in reality this would probably be a db transaction where both operations have to succeed together
         self.available += amount;
         self.transactions.insert(
             transaction_id,
-            Validated(Transaction::Deposit(transaction_id, amount)),
+            (stamp, Validated(Transaction::Deposit(transaction_id, amount))),
         );
+        Ok(())
     }
 
-    fn withdraw(&mut self, (transaction_id, amount): (u32, Decimal)) {
+    fn withdraw(
+        &mut self,
+        (transaction_id, amount): (u32, Decimal),
+        stamp: u64,
+    ) -> Result<(), LedgerError> {
         let transaction = Transaction::Withdrawal(transaction_id, amount);
-        if self.locked || self.available < amount {
+        if self.locked {
             self.transactions
-                .insert(transaction_id, Rejected(transaction));
-        } else {
-            self.available -= amount;
+                .insert(transaction_id, (stamp, Rejected(transaction)));
+            return Err(LedgerError::FrozenAccount);
+        }
+        if self.available < amount {
             self.transactions
-                .insert(transaction_id, Validated(transaction));
+                .insert(transaction_id, (stamp, Rejected(transaction)));
+            return Err(LedgerError::NotEnoughFunds);
         }
+        self.available -= amount;
+        self.transactions
+            .insert(transaction_id, (stamp, Validated(transaction)));
+        Ok(())
     }
 
-    fn dispute(&mut self, transaction_id: u32) {
-        if let Some(transaction) = self.transactions.get_mut(&transaction_id) {
-            match transaction.dispute() {
-                Some(Dispute::Deposit { amount }) => {
-                    self.available -= amount;
-                    self.held += amount;
-                }
-                Some(Dispute::Withdrawal { amount }) => {
-                    self.held += amount;
-                }
-                _ => (),
+    fn dispute(&mut self, client_id: u16, transaction_id: u32) -> Result<(), LedgerError> {
+        if self.locked {
+            return Err(LedgerError::FrozenAccount);
+        }
+        // `transactions` only ever holds entries recorded for this account, so a
+        // transaction_id that was actually filed under a different client never
+        // shows up here; this lookup alone is what keeps a dispute from reaching
+        // across into another client's ledger.
+        let (_, transaction) = self
+            .transactions
+            .get_mut(&transaction_id)
+            .ok_or(LedgerError::UnknownTx(client_id, transaction_id))?;
+        match transaction.dispute()? {
+            Some(Dispute::Deposit { amount }) => {
+                self.available -= amount;
+                self.held += amount;
             }
+            Some(Dispute::Withdrawal { amount }) => {
+                self.held += amount;
+            }
+            None => (),
         }
+        Ok(())
     }
 
-    fn resolve(&mut self, transaction_id: u32) {
-        if let Some(transaction) = self.transactions.get_mut(&transaction_id) {
-            match transaction.resolve() {
-                Some(Resolve::Deposit { amount }) => {
-                    self.available += amount;
-                    self.held -= amount;
-                }
-                Some(Resolve::Withdrawal { amount }) => {
-                    self.held -= amount;
-                }
-                _ => (),
+    fn resolve(&mut self, client_id: u16, transaction_id: u32) -> Result<(), LedgerError> {
+        if self.locked {
+            return Err(LedgerError::FrozenAccount);
+        }
+        let (_, transaction) = self
+            .transactions
+            .get_mut(&transaction_id)
+            .ok_or(LedgerError::UnknownTx(client_id, transaction_id))?;
+        match transaction.resolve()? {
+            Some(Resolve::Deposit { amount }) => {
+                self.available += amount;
+                self.held -= amount;
             }
+            Some(Resolve::Withdrawal { amount }) => {
+                self.held -= amount;
+            }
+            None => (),
         }
+        Ok(())
     }
 
-    fn chargeback(&mut self, transaction_id: u32) {
-        if let Some(transaction) = self.transactions.get_mut(&transaction_id) {
-            match transaction.chargeback() {
-                Some(_) => {
-                    self.locked = true;
-                }
-                _ => (),
-            }
+    fn chargeback(&mut self, client_id: u16, transaction_id: u32) -> Result<(), LedgerError> {
+        if self.locked {
+            return Err(LedgerError::FrozenAccount);
+        }
+        let (_, transaction) = self
+            .transactions
+            .get_mut(&transaction_id)
+            .ok_or(LedgerError::UnknownTx(client_id, transaction_id))?;
+        if transaction.chargeback()?.is_some() {
+            self.locked = true;
         }
+        Ok(())
     }
 
-    fn process(&mut self, record: TransactionRecord) {
+    fn process(&mut self, record: ValidatedTransaction, stamp: u64) -> Result<(), LedgerError> {
         match record {
-            TransactionRecord {
-                transaction_type: Deposit,
-                amount,
+            ValidatedTransaction::Deposit {
                 transaction_id,
-                ..
-            } => {
-                self.deposit((transaction_id, amount.unwrap()));
-            }
-            TransactionRecord {
-                transaction_type: Withdrawal,
                 amount,
-                transaction_id,
                 ..
-            } => {
-                self.withdraw((transaction_id, amount.unwrap()));
-            }
-            TransactionRecord {
-                transaction_type: Dispute,
+            } => self.deposit((transaction_id, amount), stamp),
+            ValidatedTransaction::Withdrawal {
                 transaction_id,
+                amount,
                 ..
-            } => self.dispute(transaction_id),
-            TransactionRecord {
-                transaction_type: Resolve,
+            } => self.withdraw((transaction_id, amount), stamp),
+            ValidatedTransaction::Dispute {
+                client_id,
                 transaction_id,
-                ..
-            } => self.resolve(transaction_id),
-            TransactionRecord {
-                transaction_type: Chargeback,
+            } => self.dispute(client_id, transaction_id),
+            ValidatedTransaction::Resolve {
+                client_id,
                 transaction_id,
-                ..
-            } => self.chargeback(transaction_id),
+            } => self.resolve(client_id, transaction_id),
+            ValidatedTransaction::Chargeback {
+                client_id,
+                transaction_id,
+            } => self.chargeback(client_id, transaction_id),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 enum RecordedTransaction {
     Validated(Transaction),
     Disputed(Transaction),
@@ -149,7 +197,7 @@ enum RecordedTransaction {
     Rejected(Transaction),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 enum Transaction {
     // transaction id, amount
     Deposit(u32, Decimal),
@@ -173,125 +221,490 @@ enum ChargeBack {
 }
 
 impl RecordedTransaction {
-    fn dispute(&mut self) -> Option<Dispute> {
+    fn dispute(&mut self) -> Result<Option<Dispute>, LedgerError> {
         match *self {
             Validated(Transaction::Deposit(transaction_id, amount)) => {
                 *self = Disputed(Transaction::Deposit(transaction_id, amount));
-                Some(Dispute::Deposit { amount })
+                Ok(Some(Dispute::Deposit { amount }))
             }
             Validated(Transaction::Withdrawal(transaction_id, amount)) => {
                 *self = Disputed(Transaction::Withdrawal(transaction_id, amount));
-                Some(Dispute::Withdrawal { amount })
+                Ok(Some(Dispute::Withdrawal { amount }))
+            }
+            Disputed(Transaction::Deposit(transaction_id, _))
+            | Disputed(Transaction::Withdrawal(transaction_id, _)) => {
+                Err(LedgerError::AlreadyDisputed(transaction_id))
             }
-            Disputed(_) => None,
             // can you dispute a resolved transaction ? I'm going to say no
-            Resolved(_) => None,
+            Resolved(_) => Ok(None),
             // can you dispute a chargeback transaction ? I'm going to say no
-            ChargedBack(_) => None,
+            ChargedBack(_) => Ok(None),
             // disputed a rejected transaction should not do anything
-            Rejected(_) => None,
+            Rejected(_) => Ok(None),
         }
     }
 
-    fn resolve(&mut self) -> Option<Resolve> {
+    fn resolve(&mut self) -> Result<Option<Resolve>, LedgerError> {
         match *self {
-            Validated(_) => None,
+            Validated(Transaction::Deposit(transaction_id, _))
+            | Validated(Transaction::Withdrawal(transaction_id, _)) => {
+                Err(LedgerError::NotDisputed(transaction_id))
+            }
             Disputed(Transaction::Deposit(transaction_id, amount)) => {
                 *self = Resolved(Transaction::Deposit(transaction_id, amount));
-                Some(Resolve::Deposit { amount })
+                Ok(Some(Resolve::Deposit { amount }))
             }
             Disputed(Transaction::Withdrawal(transaction_id, amount)) => {
                 *self = Resolved(Transaction::Withdrawal(transaction_id, amount));
-                Some(Resolve::Withdrawal { amount })
+                Ok(Some(Resolve::Withdrawal { amount }))
             }
             // can you resolve a resolved transaction ? I'm going to say no
-            Resolved(_) => None,
+            Resolved(_) => Ok(None),
             // can you resolve a chargeback transaction ? I'm going to say no
-            ChargedBack(_) => None,
+            ChargedBack(_) => Ok(None),
             // resolving a rejected transaction should not do anything
-            Rejected(_) => None,
+            Rejected(_) => Ok(None),
         }
     }
 
-    fn chargeback(&mut self) -> Option<ChargeBack> {
+    fn chargeback(&mut self) -> Result<Option<ChargeBack>, LedgerError> {
         match *self {
-            Validated(_) => None,
+            Validated(Transaction::Deposit(transaction_id, _))
+            | Validated(Transaction::Withdrawal(transaction_id, _)) => {
+                Err(LedgerError::NotDisputed(transaction_id))
+            }
             Disputed(Transaction::Deposit(transaction_id, amount)) => {
                 *self = ChargedBack(Transaction::Deposit(transaction_id, amount));
-                Some(ChargeBack::Deposit { amount })
+                Ok(Some(ChargeBack::Deposit { amount }))
             }
             Disputed(Transaction::Withdrawal(transaction_id, amount)) => {
                 *self = ChargedBack(Transaction::Withdrawal(transaction_id, amount));
-                Some(ChargeBack::Withdrawal { amount })
+                Ok(Some(ChargeBack::Withdrawal { amount }))
             }
             // can you chargeback a resolved transaction ? I'm going to say no
-            Resolved(_) => None,
+            Resolved(_) => Ok(None),
             // can you chargeback a chargeback transaction ? I'm going to say no
-            ChargedBack(_) => None,
+            ChargedBack(_) => Ok(None),
             // chargeback a rejected transaction should not do anything
-            Rejected(_) => None,
+            Rejected(_) => Ok(None),
         }
     }
 }
 
-#[derive(Default)]
-struct Bank {
-    // TODO add clock to ensure transaction order
-    // next_clock: u64,
+/// Storage for the per-client ledger, kept separate from the processing
+/// logic so that `Bank` doesn't care whether accounts live entirely in RAM
+/// or spill to disk once an input gets larger than memory allows. Mirrors
+/// the `ActStore`/`MemActStore` split the `act` crate uses for the same
+/// reason.
+trait AccountStore: Default + Send {
+    /// Looks up an account that may need to be faulted back in from disk
+    /// first, so this always takes `&mut self` even for stores that never
+    /// spill; there is no read-only accessor that could misreport a spilled
+    /// account as absent.
+    fn get_mut(&mut self, client_id: u16) -> Option<&mut Account>;
+    fn entry_or_default(&mut self, client_id: u16) -> &mut Account;
+    /// Every known account, as the `Client` row it exports to at the end of a run.
+    fn iter(&self) -> Box<dyn Iterator<Item = Client> + '_>;
+    /// Folds another worker's disjoint client set into this one.
+    fn merge(&mut self, other: Self)
+    where
+        Self: Sized;
+    /// Builds the store a parallel worker processes its shard with. Stores
+    /// with no shared, mutable resources (e.g. an in-memory map) can just
+    /// use `Self::default()`; stores backed by something workers could
+    /// otherwise clobber each other through (e.g. a spill directory) use
+    /// `worker_index` to keep themselves isolated.
+    fn for_worker(worker_index: usize) -> Self
+    where
+        Self: Sized,
+    {
+        let _ = worker_index;
+        Self::default()
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemAccountStore {
     accounts: BTreeMap<u16, Account>,
 }
 
-impl Bank {
-    fn process_transaction(&mut self, record: TransactionRecord) {
-        let account = self
-            .accounts
-            .entry(record.client_id)
-            .or_insert_with(|| Account::default());
-        // let next_clock = self.next_clock;
-        // self.next_clock += 1;
-        account.process(record);
+impl AccountStore for MemAccountStore {
+    fn get_mut(&mut self, client_id: u16) -> Option<&mut Account> {
+        self.accounts.get_mut(&client_id)
+    }
+
+    fn entry_or_default(&mut self, client_id: u16) -> &mut Account {
+        self.accounts.entry(client_id).or_insert_with(Account::default)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Client> + '_> {
+        Box::new(
+            self.accounts
+                .iter()
+                .map(|(&client_id, account)| account.for_export(client_id)),
+        )
+    }
+
+    fn merge(&mut self, other: MemAccountStore) {
+        self.accounts.extend(other.accounts);
+    }
+}
+
+/// Keeps at most `capacity` accounts in memory and spills the rest to
+/// `spill_dir` as JSON, so a multi-gigabyte input with millions of clients
+/// doesn't have to fit in RAM at once. Eviction just picks the
+/// lowest-numbered hot client id as a cheap stand-in for a real LRU policy.
+struct DiskAccountStore {
+    hot: BTreeMap<u16, Account>,
+    capacity: usize,
+    spill_dir: std::path::PathBuf,
+}
+
+impl DiskAccountStore {
+    /// `spill_dir` is wiped before use: it is expected to be a directory
+    /// scoped to this run (or this worker's share of it), never a path
+    /// reused across runs, so starting from a clean slate here is always
+    /// correct and protects against leftover files from a prior crashed run.
+    fn new(spill_dir: impl Into<std::path::PathBuf>, capacity: usize) -> Self {
+        let spill_dir = spill_dir.into();
+        let _ = std::fs::remove_dir_all(&spill_dir);
+        let _ = std::fs::create_dir_all(&spill_dir);
+        DiskAccountStore {
+            hot: BTreeMap::new(),
+            capacity,
+            spill_dir,
+        }
+    }
+
+    fn spill_path(&self, client_id: u16) -> std::path::PathBuf {
+        self.spill_dir.join(format!("{}.json", client_id))
+    }
+
+    /// Reads a spilled account for `client_id`, if one exists. The spill
+    /// file is only removed once it has been parsed back successfully, so a
+    /// corrupt or partially-written file never costs the client its balance
+    /// silently — it's left on disk and the failure is logged instead.
+    fn take_spilled(&self, client_id: u16) -> Option<Account> {
+        let path = self.spill_path(client_id);
+        let bytes = std::fs::read(&path).ok()?;
+        match serde_json::from_slice(&bytes) {
+            Ok(account) => {
+                let _ = std::fs::remove_file(&path);
+                Some(account)
+            }
+            Err(err) => {
+                eprintln!(
+                    "failed to read spilled account for client {} from {}, leaving it in place: {}",
+                    client_id,
+                    path.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    fn spill_coldest(&mut self) {
+        if let Some(&client_id) = self.hot.keys().next() {
+            if let Some(account) = self.hot.remove(&client_id) {
+                if let Ok(bytes) = serde_json::to_vec(&account) {
+                    let _ = std::fs::write(self.spill_path(client_id), bytes);
+                }
+            }
+        }
+    }
+}
+
+/// Spill files live only as long as the store that owns them: the
+/// directory is wiped on construction (see [`DiskAccountStore::new`]) and
+/// removed again here so a finished run never leaves stale `{client}.json`
+/// files for a later run to mistakenly fault back in.
+impl Drop for DiskAccountStore {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.spill_dir);
+    }
+}
+
+/// Base spill directory: `SIMPLIFIED_BANK_SPILL_DIR` when set (used as is, so
+/// runs given the same value share it), otherwise a temp directory scoped to
+/// this process so concurrent or crashed runs never share one. Pure and
+/// side-effect-free: callers decide when (and
+/// whether) to actually create/clear it via [`DiskAccountStore::new`].
+fn default_spill_base() -> std::path::PathBuf {
+    env::var("SIMPLIFIED_BANK_SPILL_DIR")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|_| {
+            std::env::temp_dir().join(format!("simplified_bank_spill-{}", std::process::id()))
+        })
+}
+
+fn default_disk_capacity() -> usize {
+    env::var("SIMPLIFIED_BANK_HOT_CAPACITY")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|&capacity| capacity > 0)
+        .unwrap_or(1024)
+}
+
+impl Default for DiskAccountStore {
+    fn default() -> Self {
+        DiskAccountStore::new(default_spill_base(), default_disk_capacity())
+    }
+}
+
+impl AccountStore for DiskAccountStore {
+    fn get_mut(&mut self, client_id: u16) -> Option<&mut Account> {
+        if !self.hot.contains_key(&client_id) {
+            let account = self.take_spilled(client_id)?;
+            if self.hot.len() >= self.capacity {
+                self.spill_coldest();
+            }
+            self.hot.insert(client_id, account);
+        }
+        self.hot.get_mut(&client_id)
+    }
+
+    fn entry_or_default(&mut self, client_id: u16) -> &mut Account {
+        if !self.hot.contains_key(&client_id) {
+            let account = self.take_spilled(client_id).unwrap_or_default();
+            if self.hot.len() >= self.capacity {
+                self.spill_coldest();
+            }
+            self.hot.insert(client_id, account);
+        }
+        self.hot.get_mut(&client_id).expect("just inserted above")
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Client> + '_> {
+        let hot = self
+            .hot
+            .iter()
+            .map(|(&client_id, account)| account.for_export(client_id));
+        let cold = std::fs::read_dir(&self.spill_dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let file_name = entry.file_name();
+                let client_id: u16 = std::path::Path::new(&file_name)
+                    .file_stem()?
+                    .to_str()?
+                    .parse()
+                    .ok()?;
+                let bytes = std::fs::read(entry.path()).ok()?;
+                let account: Account = serde_json::from_slice(&bytes).ok()?;
+                Some(account.for_export(client_id))
+            });
+        Box::new(hot.chain(cold))
+    }
+
+    /// Folds in `other`'s hot set, then claims its spilled files (each
+    /// worker spills to its own isolated directory, see [`Self::for_worker`],
+    /// so these never collide with anything already in `self.spill_dir`).
+    fn merge(&mut self, mut other: DiskAccountStore) {
+        for (client_id, account) in std::mem::take(&mut other.hot) {
+            if self.hot.len() >= self.capacity && !self.hot.contains_key(&client_id) {
+                self.spill_coldest();
+            }
+            self.hot.insert(client_id, account);
+        }
+        if let Ok(entries) = std::fs::read_dir(&other.spill_dir) {
+            for entry in entries.flatten() {
+                let _ = std::fs::rename(entry.path(), self.spill_dir.join(entry.file_name()));
+            }
+        }
+    }
+
+    /// Spills to its own subdirectory of the run's base `spill_dir`, named
+    /// after `worker_index`, so concurrent workers in the same run never
+    /// write to the same `{client}.json` path even if a future change to
+    /// routing stops keeping client ids disjoint across workers. Built from
+    /// [`default_spill_base`] directly (not `Self::default()`) so creating a
+    /// worker's subdirectory never re-clears the shared base directory that
+    /// the merge target (and every other worker) also lives under.
+    fn for_worker(worker_index: usize) -> Self {
+        let spill_dir = default_spill_base().join(format!("worker-{}", worker_index));
+        DiskAccountStore::new(spill_dir, default_disk_capacity())
+    }
+}
+
+/// A monotonic arrival-order sequence, shared by every processing pipeline,
+/// that also rejects a reused deposit/withdrawal `transaction_id` instead of
+/// letting it clobber the account's prior record of it.
+#[derive(Default)]
+struct TxClock {
+    next: u64,
+    seen_tx_ids: BTreeSet<u32>,
+}
+
+impl TxClock {
+    fn stamp(&mut self, record: &ValidatedTransaction) -> Result<u64, LedgerError> {
+        let stamp = self.next;
+        self.next += 1;
+        if let ValidatedTransaction::Deposit { transaction_id, .. }
+        | ValidatedTransaction::Withdrawal { transaction_id, .. } = *record
+        {
+            if !self.seen_tx_ids.insert(transaction_id) {
+                return Err(LedgerError::DuplicateTx(transaction_id));
+            }
+        }
+        Ok(stamp)
     }
 }
 
-async fn parse_transactions(input_file: &str) -> Result<Bank> {
+#[derive(Default)]
+struct Bank<S: AccountStore = MemAccountStore> {
+    clock: TxClock,
+    accounts: S,
+}
+
+impl<S: AccountStore> Bank<S> {
+    fn process_transaction(&mut self, record: ValidatedTransaction) -> Result<(), LedgerError> {
+        let stamp = self.clock.stamp(&record)?;
+        let account = self.accounts.entry_or_default(record.client_id());
+        account.process(record, stamp)
+    }
+}
+
+async fn parse_transactions(input_file: &str) -> Result<Bank<MemAccountStore>> {
+    parse_transactions_into(input_file).await
+}
+
+/// Same as [`parse_transactions`], but lets the caller pick the backing
+/// `AccountStore` (e.g. [`DiskAccountStore`] for inputs larger than memory).
+async fn parse_transactions_into<S: AccountStore>(input_file: &str) -> Result<Bank<S>> {
     let mut reader = AsyncReaderBuilder::new()
         .trim(Trim::All)
         .create_deserializer(File::open(&input_file).await.with_context(|| {
             format!("Failed to read transaction input file from {}", &input_file)
         })?);
     let mut bank = Bank::default();
-    let mut records = AsyncDeserializer::deserialize::<TransactionRecord>(&mut reader);
+    let mut records = AsyncDeserializer::deserialize::<ValidatedTransaction>(&mut reader);
     while let Some(record) = records.next().await {
         // TODO: this is probably best expressed as a combinator
-        if let Ok(record) = record {
-            bank.process_transaction(record);
-        } else {
-            eprintln!(
-                "failed to parse into record, ignoring transaction, received input {:?}",
-                record
-            );
+        match record {
+            Ok(record) => {
+                if let Err(err) = bank.process_transaction(record) {
+                    eprintln!("refusing transaction: {}", err);
+                }
+            }
+            Err(err) => {
+                eprintln!("failed to parse into record, ignoring transaction: {}", err);
+            }
         }
     }
     Ok(bank)
 }
 
+/// Routes each record to worker `client_id % worker_count` over its own
+/// FIFO channel, so every client's records are applied, in order, by exactly
+/// one worker. Workers run independent `S` stores that get merged once the
+/// input stream ends and every worker has caught up.
+async fn parse_transactions_parallel<S: AccountStore + 'static>(
+    input_file: &str,
+    worker_count: usize,
+) -> Result<Bank<S>> {
+    let worker_count = worker_count.max(1);
+    // built before any worker starts, so for stores backed by a shared
+    // resource (e.g. a spill directory) this establishes a clean base that
+    // workers can safely build their own isolated state underneath
+    let mut bank = Bank::<S>::default();
+    let mut senders = Vec::with_capacity(worker_count);
+    let mut workers = Vec::with_capacity(worker_count);
+    for worker_index in 0..worker_count {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(u64, ValidatedTransaction)>();
+        senders.push(sender);
+        workers.push(tokio::spawn(async move {
+            let mut store = S::for_worker(worker_index);
+            while let Some((stamp, record)) = receiver.recv().await {
+                let account = store.entry_or_default(record.client_id());
+                if let Err(err) = account.process(record, stamp) {
+                    eprintln!("refusing transaction: {}", err);
+                }
+            }
+            store
+        }));
+    }
+
+    let mut reader = AsyncReaderBuilder::new()
+        .trim(Trim::All)
+        .create_deserializer(File::open(&input_file).await.with_context(|| {
+            format!("Failed to read transaction input file from {}", &input_file)
+        })?);
+    let mut records = AsyncDeserializer::deserialize::<ValidatedTransaction>(&mut reader);
+    // the router sees every record in true input order, so it is the only place
+    // that needs to assign arrival-order stamps and enforce global tx-id uniqueness
+    let mut clock = TxClock::default();
+    while let Some(record) = records.next().await {
+        match record {
+            Ok(record) => match clock.stamp(&record) {
+                Ok(stamp) => {
+                    let worker = record.client_id() as usize % worker_count;
+                    // a closed receiver only happens if that worker task panicked
+                    let _ = senders[worker].send((stamp, record));
+                }
+                Err(err) => eprintln!("refusing transaction: {}", err),
+            },
+            Err(err) => {
+                eprintln!("failed to parse into record, ignoring transaction: {}", err);
+            }
+        }
+    }
+    drop(senders);
+
+    for worker in workers {
+        let store = worker.await.context("worker task panicked")?;
+        bank.accounts.merge(store);
+    }
+    Ok(bank)
+}
+
+fn default_worker_count() -> usize {
+    env::var("SIMPLIFIED_BANK_WORKERS")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|&count| count > 0)
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|count| count.get())
+                .unwrap_or(1)
+        })
+}
+
+/// Parses and exports `input_file` through `S`, e.g. [`DiskAccountStore`] for
+/// an input whose client set doesn't fit in memory at once.
+async fn run<S: AccountStore + 'static>(input_file: &str, worker_count: usize) -> Result<()> {
+    let bank = parse_transactions_parallel::<S>(input_file, worker_count).await?;
+    let mut writer = AsyncSerializer::from_writer(io::stdout());
+    for client in bank.accounts.iter() {
+        writer.serialize(client).await?;
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
+    if args.len() < 2 || args.len() > 3 {
         bail!(
-            "This program supports exactly one    
-         => (*client) argument passed, it should be the filename. I've receieved {:?}",
+            "This program supports one required (input file) and one optional (worker count)
+         => argument, it should be the filename followed by an optional worker count. I've receieved {:?}",
             args
         );
     }
-    let bank = parse_transactions(&args[1]).await?;
-    let mut writer = AsyncSerializer::from_writer(io::stdout());
-    for (id, account) in &bank.accounts {
-        let client = account.for_export(*id);
-        writer.serialize(client).await?;
+    let worker_count = match args.get(2) {
+        Some(value) => value
+            .parse()
+            .context("worker count must be a positive integer")?,
+        None => default_worker_count(),
+    };
+    // SIMPLIFIED_BANK_STORE=disk switches to DiskAccountStore for inputs whose
+    // client set doesn't fit in memory; anything else keeps the default, fully
+    // in-memory store.
+    match env::var("SIMPLIFIED_BANK_STORE").as_deref() {
+        Ok("disk") => run::<DiskAccountStore>(&args[1], worker_count).await,
+        _ => run::<MemAccountStore>(&args[1], worker_count).await,
     }
-    Ok(())
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -315,6 +728,112 @@ enum TransactionType {
     Chargeback,
 }
 
+/// A `TransactionRecord` that has passed structural validation: deposits and
+/// withdrawals carry a normalized, positive amount; dispute, resolve and
+/// chargeback rows never carry one. Deserializing straight into this type
+/// keeps the amount-shaped bugs out of the processing hot path.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(try_from = "TransactionRecord")]
+enum ValidatedTransaction {
+    Deposit {
+        client_id: u16,
+        transaction_id: u32,
+        amount: Decimal,
+    },
+    Withdrawal {
+        client_id: u16,
+        transaction_id: u32,
+        amount: Decimal,
+    },
+    Dispute {
+        client_id: u16,
+        transaction_id: u32,
+    },
+    Resolve {
+        client_id: u16,
+        transaction_id: u32,
+    },
+    Chargeback {
+        client_id: u16,
+        transaction_id: u32,
+    },
+}
+
+impl ValidatedTransaction {
+    fn client_id(&self) -> u16 {
+        match *self {
+            ValidatedTransaction::Deposit { client_id, .. }
+            | ValidatedTransaction::Withdrawal { client_id, .. }
+            | ValidatedTransaction::Dispute { client_id, .. }
+            | ValidatedTransaction::Resolve { client_id, .. }
+            | ValidatedTransaction::Chargeback { client_id, .. } => client_id,
+        }
+    }
+}
+
+impl TryFrom<TransactionRecord> for ValidatedTransaction {
+    type Error = LedgerError;
+
+    fn try_from(record: TransactionRecord) -> Result<Self, Self::Error> {
+        let TransactionRecord {
+            transaction_type,
+            client_id,
+            transaction_id,
+            amount,
+        } = record;
+        match transaction_type {
+            Deposit => Ok(ValidatedTransaction::Deposit {
+                client_id,
+                transaction_id,
+                amount: validate_amount(amount)?,
+            }),
+            Withdrawal => Ok(ValidatedTransaction::Withdrawal {
+                client_id,
+                transaction_id,
+                amount: validate_amount(amount)?,
+            }),
+            Dispute => {
+                validate_no_amount(amount)?;
+                Ok(ValidatedTransaction::Dispute {
+                    client_id,
+                    transaction_id,
+                })
+            }
+            Resolve => {
+                validate_no_amount(amount)?;
+                Ok(ValidatedTransaction::Resolve {
+                    client_id,
+                    transaction_id,
+                })
+            }
+            Chargeback => {
+                validate_no_amount(amount)?;
+                Ok(ValidatedTransaction::Chargeback {
+                    client_id,
+                    transaction_id,
+                })
+            }
+        }
+    }
+}
+
+// deposits/withdrawals must carry a positive amount, clamped to four decimal places
+fn validate_amount(amount: Option<Decimal>) -> Result<Decimal, LedgerError> {
+    let amount = amount.ok_or(LedgerError::MissingAmount)?.round_dp(4);
+    if amount <= Decimal::ZERO {
+        return Err(LedgerError::MissingAmount);
+    }
+    Ok(amount)
+}
+
+// dispute/resolve/chargeback rows reference a prior transaction and never carry their own amount
+fn validate_no_amount(amount: Option<Decimal>) -> Result<(), LedgerError> {
+    if amount.is_some() {
+        return Err(LedgerError::MissingAmount);
+    }
+    Ok(())
+}
+
 #[derive(Debug, Serialize, PartialEq)]
 struct Client {
     id: u16,
@@ -331,10 +850,10 @@ mod tests {
 
     #[tokio::test]
     async fn example_input() {
-        let parsed = parse_transactions("./data/example_input.csv")
+        let mut parsed = parse_transactions("./data/example_input.csv")
             .await
             .expect("failed parsing example input");
-        let parsed_client_1 = parsed.accounts.get(&1).unwrap().for_export(1);
+        let parsed_client_1 = parsed.accounts.get_mut(1).unwrap().for_export(1);
         assert_eq!(
             parsed_client_1,
             Client {
@@ -345,7 +864,7 @@ mod tests {
                 locked: false,
             },
         );
-        let parsed_client_2 = parsed.accounts.get(&2).unwrap().for_export(2);
+        let parsed_client_2 = parsed.accounts.get_mut(2).unwrap().for_export(2);
         assert_eq!(
             parsed_client_2,
             Client {
@@ -360,10 +879,10 @@ mod tests {
 
     #[tokio::test]
     async fn dispute_deposit_with_resolution() {
-        let parsed = parse_transactions("./data/dispute_deposit_with_resolution.csv")
+        let mut parsed = parse_transactions("./data/dispute_deposit_with_resolution.csv")
             .await
             .expect("failed parsing example input");
-        let parsed_client_1 = parsed.accounts.get(&1).unwrap().for_export(1);
+        let parsed_client_1 = parsed.accounts.get_mut(1).unwrap().for_export(1);
         assert_eq!(
             parsed_client_1,
             Client {
@@ -378,10 +897,10 @@ mod tests {
 
     #[tokio::test]
     async fn dispute_deposit_with_chargeback() {
-        let parsed = parse_transactions("./data/dispute_deposit_with_chargeback.csv")
+        let mut parsed = parse_transactions("./data/dispute_deposit_with_chargeback.csv")
             .await
             .expect("failed parsing example input");
-        let parsed_client_1 = parsed.accounts.get(&1).unwrap().for_export(1);
+        let parsed_client_1 = parsed.accounts.get_mut(1).unwrap().for_export(1);
         assert_eq!(
             parsed_client_1,
             Client {
@@ -396,10 +915,10 @@ mod tests {
 
     #[tokio::test]
     async fn dispute_withdrawal_with_resolution() {
-        let parsed = parse_transactions("./data/dispute_withdrawal_with_resolution.csv")
+        let mut parsed = parse_transactions("./data/dispute_withdrawal_with_resolution.csv")
             .await
             .expect("failed parsing example input");
-        let parsed_client_1 = parsed.accounts.get(&1).unwrap().for_export(1);
+        let parsed_client_1 = parsed.accounts.get_mut(1).unwrap().for_export(1);
         assert_eq!(
             parsed_client_1,
             Client {
@@ -414,10 +933,10 @@ mod tests {
 
     #[tokio::test]
     async fn thief() {
-        let parsed = parse_transactions("./data/thief.csv")
+        let mut parsed = parse_transactions("./data/thief.csv")
             .await
             .expect("failed parsing example input");
-        let parsed_client_1 = parsed.accounts.get(&1).unwrap().for_export(1);
+        let parsed_client_1 = parsed.accounts.get_mut(1).unwrap().for_export(1);
         assert_eq!(
             parsed_client_1,
             Client {
@@ -432,10 +951,10 @@ mod tests {
 
     #[tokio::test]
     async fn locked_account_should_be_able_to_deposit() {
-        let parsed = parse_transactions("./data/locked_account_deposit.csv")
+        let mut parsed = parse_transactions("./data/locked_account_deposit.csv")
             .await
             .expect("failed parsing example input");
-        let parsed_client_1 = parsed.accounts.get(&1).unwrap().for_export(1);
+        let parsed_client_1 = parsed.accounts.get_mut(1).unwrap().for_export(1);
         assert_eq!(
             parsed_client_1,
             Client {
@@ -450,10 +969,10 @@ mod tests {
 
     #[tokio::test]
     async fn locked_account_cant_withdraw() {
-        let parsed = parse_transactions("./data/locked_account_withdrawal.csv")
+        let mut parsed = parse_transactions("./data/locked_account_withdrawal.csv")
             .await
             .expect("failed parsing example input");
-        let parsed_client_1 = parsed.accounts.get(&1).unwrap().for_export(1);
+        let parsed_client_1 = parsed.accounts.get_mut(1).unwrap().for_export(1);
         assert_eq!(
             parsed_client_1,
             Client {
@@ -465,4 +984,212 @@ mod tests {
             },
         );
     }
+
+    #[test]
+    fn withdraw_with_insufficient_funds_is_refused() {
+        let mut account = Account::default();
+        account.deposit((1, Decimal::from_str("1").unwrap()), 0).unwrap();
+        assert_eq!(
+            account.withdraw((2, Decimal::from_str("2").unwrap()), 1),
+            Err(LedgerError::NotEnoughFunds),
+        );
+    }
+
+    #[test]
+    fn dispute_of_unknown_tx_is_refused() {
+        let mut account = Account::default();
+        assert_eq!(account.dispute(1, 1), Err(LedgerError::UnknownTx(1, 1)));
+    }
+
+    #[test]
+    fn dispute_of_already_disputed_tx_is_refused() {
+        let mut account = Account::default();
+        account.deposit((1, Decimal::from_str("1").unwrap()), 0).unwrap();
+        account.dispute(1, 1).unwrap();
+        assert_eq!(
+            account.dispute(1, 1),
+            Err(LedgerError::AlreadyDisputed(1)),
+        );
+    }
+
+    #[test]
+    fn resolve_of_never_disputed_tx_is_refused() {
+        let mut account = Account::default();
+        account.deposit((1, Decimal::from_str("1").unwrap()), 0).unwrap();
+        assert_eq!(account.resolve(1, 1), Err(LedgerError::NotDisputed(1)));
+    }
+
+    #[test]
+    fn mutating_a_frozen_account_is_refused() {
+        let mut account = Account::default();
+        account.deposit((1, Decimal::from_str("1").unwrap()), 0).unwrap();
+        account.dispute(1, 1).unwrap();
+        account.chargeback(1, 1).unwrap();
+        assert!(account.locked);
+        assert_eq!(account.dispute(1, 2), Err(LedgerError::FrozenAccount));
+        assert_eq!(account.resolve(1, 2), Err(LedgerError::FrozenAccount));
+        assert_eq!(account.chargeback(1, 2), Err(LedgerError::FrozenAccount));
+    }
+
+    #[test]
+    fn dispute_naming_the_wrong_client_is_unknown_to_that_account() {
+        // client 1's account never recorded tx 7 because it belongs to client 2;
+        // from client 1's side this must look exactly like an unknown tx.
+        let mut client_2_account = Account::default();
+        client_2_account
+            .deposit((7, Decimal::from_str("1").unwrap()), 0)
+            .unwrap();
+        let mut client_1_account = Account::default();
+        assert_eq!(
+            client_1_account.dispute(1, 7),
+            Err(LedgerError::UnknownTx(1, 7)),
+        );
+    }
+
+    fn record(
+        transaction_type: TransactionType,
+        transaction_id: u32,
+        amount: Option<&str>,
+    ) -> TransactionRecord {
+        TransactionRecord {
+            transaction_type,
+            client_id: 1,
+            transaction_id,
+            amount: amount.map(|amount| Decimal::from_str(amount).unwrap()),
+        }
+    }
+
+    #[test]
+    fn deposit_with_missing_amount_is_rejected() {
+        let record = record(TransactionType::Deposit, 1, None);
+        assert_eq!(
+            ValidatedTransaction::try_from(record),
+            Err(LedgerError::MissingAmount),
+        );
+    }
+
+    #[test]
+    fn withdrawal_with_non_positive_amount_is_rejected() {
+        let record = record(TransactionType::Withdrawal, 1, Some("0"));
+        assert_eq!(
+            ValidatedTransaction::try_from(record),
+            Err(LedgerError::MissingAmount),
+        );
+    }
+
+    #[test]
+    fn dispute_carrying_an_amount_is_rejected() {
+        let record = record(TransactionType::Dispute, 1, Some("1"));
+        assert_eq!(
+            ValidatedTransaction::try_from(record),
+            Err(LedgerError::MissingAmount),
+        );
+    }
+
+    #[test]
+    fn deposit_amount_is_clamped_to_four_decimal_places() {
+        let record = record(TransactionType::Deposit, 1, Some("1.23456"));
+        assert_eq!(
+            ValidatedTransaction::try_from(record),
+            Ok(ValidatedTransaction::Deposit {
+                client_id: 1,
+                transaction_id: 1,
+                amount: Decimal::from_str("1.2346").unwrap(),
+            }),
+        );
+    }
+
+    #[tokio::test]
+    async fn parallel_shard_matches_sequential_processing() {
+        let mut parsed =
+            parse_transactions_parallel::<MemAccountStore>("./data/parallel_shard.csv", 2)
+                .await
+                .expect("failed parsing example input");
+        assert_eq!(
+            parsed.accounts.get_mut(1).unwrap().for_export(1),
+            Client {
+                id: 1,
+                available: Decimal::from_str("3").unwrap(),
+                held: Decimal::ZERO,
+                total: Decimal::from_str("3").unwrap(),
+                locked: false,
+            },
+        );
+        assert_eq!(
+            parsed.accounts.get_mut(2).unwrap().for_export(2),
+            Client {
+                id: 2,
+                available: Decimal::from_str("7").unwrap(),
+                held: Decimal::ZERO,
+                total: Decimal::from_str("7").unwrap(),
+                locked: false,
+            },
+        );
+    }
+
+    #[test]
+    fn reused_transaction_id_is_refused_instead_of_overwriting() {
+        let mut bank = Bank::<MemAccountStore>::default();
+        let first = ValidatedTransaction::Deposit {
+            client_id: 1,
+            transaction_id: 1,
+            amount: Decimal::from_str("1").unwrap(),
+        };
+        let duplicate = ValidatedTransaction::Deposit {
+            client_id: 1,
+            transaction_id: 1,
+            amount: Decimal::from_str("2").unwrap(),
+        };
+        bank.process_transaction(first).unwrap();
+        assert_eq!(
+            bank.process_transaction(duplicate),
+            Err(LedgerError::DuplicateTx(1)),
+        );
+        assert_eq!(
+            bank.accounts.get_mut(1).unwrap().for_export(1).available,
+            Decimal::from_str("1").unwrap(),
+        );
+    }
+
+    #[test]
+    fn disk_store_faults_evicted_accounts_back_in() {
+        // `DiskAccountStore::new` clears this on construction and its `Drop`
+        // removes it again once `bank` goes out of scope, so no manual
+        // cleanup is needed around this test.
+        let spill_dir = std::env::temp_dir().join("simplified_bank_spill_test_fault_in");
+        let mut bank = Bank {
+            clock: TxClock::default(),
+            accounts: DiskAccountStore::new(spill_dir, 1),
+        };
+        bank.process_transaction(ValidatedTransaction::Deposit {
+            client_id: 1,
+            transaction_id: 1,
+            amount: Decimal::from_str("5").unwrap(),
+        })
+        .unwrap();
+        // client 2's entry evicts client 1 to disk, since capacity is 1
+        bank.process_transaction(ValidatedTransaction::Deposit {
+            client_id: 2,
+            transaction_id: 2,
+            amount: Decimal::from_str("7").unwrap(),
+        })
+        .unwrap();
+        // disputing client 1 must fault its spilled account back in rather
+        // than silently treating it as unknown
+        bank.process_transaction(ValidatedTransaction::Dispute {
+            client_id: 1,
+            transaction_id: 1,
+        })
+        .unwrap();
+        assert_eq!(
+            bank.accounts.get_mut(1).unwrap().for_export(1),
+            Client {
+                id: 1,
+                available: Decimal::ZERO,
+                held: Decimal::from_str("5").unwrap(),
+                total: Decimal::from_str("5").unwrap(),
+                locked: false,
+            },
+        );
+    }
 }